@@ -15,9 +15,9 @@ use tokio_uds::UnixListener;
 
 fn main() {
     let socket = UnixListener::bind("echo.sock").unwrap();
-    match Safeword::default().run(
-        socket
-            .incoming()
+    match Safeword::default().run_graceful(|handle| {
+        handle
+            .wrap(socket.incoming())
             .map_err(|err| eprintln!("{:?}", err))
             .for_each(|stream| {
                 let (reader, writer) = stream.split();
@@ -27,11 +27,11 @@ fn main() {
                     }
                     Ok(())
                 }))
-            }),
-    ) {
-        Ok(()) => {
+            })
+    }) {
+        Ok(signal) => {
             fs::remove_file("echo.sock").unwrap();
-            eprintln!("application closed cleanly");
+            eprintln!("application closed cleanly after {:?}", signal);
         }
         Err(err) => eprintln!("application unexpectedly stopped: {:?}", err),
     }
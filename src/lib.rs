@@ -7,20 +7,198 @@
 //! of [`tokio::run`] and know whether your application was asked to stop, or stopped for another
 //! reason (such as the future finishing earlier than you expected).
 //!
-//! Use this library with [`Safeword::run`]. Inspect the cause of why your code might have failed
-//! with [`Shutdown`].
+//! Use this library with [`Safeword::run`], or [`Safeword::run_graceful`] if your future needs to
+//! finish draining in-flight work (such as open connections) before exiting. Inspect which signal
+//! stopped your code with the returned [`Signal`], or the cause of why it might have failed with
+//! [`Shutdown`].
+//!
+//! Safeword builds on Unix signals by default, but [`Safeword::ctrl_c`] also works on Windows.
 
+extern crate stream_cancel;
 extern crate tokio;
 extern crate tokio_signal;
 
 use std::error::Error;
 use std::fmt::{self, Debug, Display};
 use std::io;
+use std::time::{Duration, Instant};
+use stream_cancel::Valve;
 use tokio::prelude::future::{self, Either, Future};
-use tokio::prelude::stream::Stream;
+use tokio::prelude::stream::{self, Stream};
+use tokio::prelude::{Async, Poll};
 use tokio::runtime::Runtime;
+use tokio::timer::{self, Delay};
+#[cfg(unix)]
 use tokio_signal::unix::libc::{c_int, SIGINT, SIGTERM};
 
+/// A Unix signal, or the portable Ctrl-C event, used both to configure what [`Safeword`] watches
+/// for and to report which one fired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// A Unix signal, identified by its number.
+    #[cfg(unix)]
+    Unix(c_int),
+    /// The Ctrl-C control event (SIGINT on Unix, the `CTRL_C_EVENT` console event on Windows).
+    CtrlC,
+}
+
+/// Wraps an individual signal or the Ctrl-C event in a [`Stream`] that yields a [`Signal`]
+/// identifying itself every time it fires.
+fn signal_stream<T, E>(
+    signal: Signal,
+) -> Box<dyn Stream<Item = Signal, Error = Shutdown<T, E>> + Send>
+where
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    match signal {
+        #[cfg(unix)]
+        Signal::Unix(number) => Box::new(
+            tokio_signal::unix::Signal::new(number)
+                .flatten_stream()
+                .map(move |_| Signal::Unix(number))
+                .map_err(Shutdown::SignalError),
+        ),
+        Signal::CtrlC => Box::new(ctrl_c_stream()),
+    }
+}
+
+#[cfg(unix)]
+fn ctrl_c_stream<T, E>() -> impl Stream<Item = Signal, Error = Shutdown<T, E>> + Send {
+    tokio_signal::unix::Signal::new(SIGINT)
+        .flatten_stream()
+        .map(|_| Signal::CtrlC)
+        .map_err(Shutdown::SignalError)
+}
+
+#[cfg(windows)]
+fn ctrl_c_stream<T, E>() -> impl Stream<Item = Signal, Error = Shutdown<T, E>> + Send {
+    tokio_signal::windows::ctrl_c()
+        .flatten_stream()
+        .map(|_| Signal::CtrlC)
+        .map_err(Shutdown::SignalError)
+}
+
+/// Merges every configured signal into a single [`Stream`].
+fn terminate_stream<T, E>(
+    signals: Vec<Signal>,
+) -> Box<dyn Stream<Item = Signal, Error = Shutdown<T, E>> + Send>
+where
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    signals.into_iter().fold(
+        Box::new(stream::empty()) as Box<dyn Stream<Item = Signal, Error = Shutdown<T, E>> + Send>,
+        |acc, signal| Box::new(acc.select(signal_stream(signal))),
+    )
+}
+
+/// Selects over every configured signal, resolving with whichever one arrives first.
+fn signal_future<T, E>(signals: Vec<Signal>) -> impl Future<Item = Signal, Error = Shutdown<T, E>>
+where
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    first_signal(signals).map(|(signal, _)| signal)
+}
+
+/// Selects over every configured signal, resolving with whichever one arrives first along with a
+/// stream of whatever arrives afterwards (so a caller can keep watching for a second signal).
+///
+/// If `signals` is empty, there is nothing to ever resolve with, so this returns a future that
+/// never completes: callers just end up waiting on whatever they select it against instead.
+fn first_signal<T, E>(
+    signals: Vec<Signal>,
+) -> Box<
+    dyn Future<
+            Item = (Signal, Box<dyn Stream<Item = Signal, Error = Shutdown<T, E>> + Send>),
+            Error = Shutdown<T, E>,
+        > + Send,
+>
+where
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    if signals.is_empty() {
+        return Box::new(future::empty());
+    }
+
+    Box::new(
+        terminate_stream(signals)
+            .into_future()
+            .map(|(signal, remaining)| {
+                (
+                    signal.expect("a terminate stream should never end"),
+                    remaining,
+                )
+            })
+            .map_err(|(err, _)| err),
+    )
+}
+
+/// Spawns a task per reload signal that invokes its callback on every delivery, for as long as
+/// `runtime` keeps running.
+#[cfg(unix)]
+fn spawn_reload_signals(
+    runtime: &mut Runtime,
+    reload_signals: Vec<(c_int, Box<dyn FnMut() + Send>)>,
+) {
+    for (signal, mut callback) in reload_signals {
+        runtime.spawn(
+            tokio_signal::unix::Signal::new(signal)
+                .flatten_stream()
+                .map_err(|_| ())
+                .for_each(move |_| {
+                    callback();
+                    Ok(())
+                }),
+        );
+    }
+}
+
+/// A [`Stream`] that never yields an item and never ends on its own, used to turn a [`Valve`]
+/// into a [`Future`] that resolves once the valve is closed.
+struct Pending;
+
+impl Stream for Pending {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<()>, ()> {
+        Ok(Async::NotReady)
+    }
+}
+
+/// A handle to the cancellation valve passed to the future given to [`Safeword::run_graceful`].
+///
+/// Wrap any [`Stream`] your future owns with [`Handle::wrap`] so that, once a signal arrives, it
+/// yields `None` and ends on its own instead of being dropped mid-poll. Alternatively, `select` or
+/// `join` on the `Handle` itself (it's a [`Future`]) if you'd rather be told about the signal
+/// directly instead of threading a wrapped stream through your future.
+#[derive(Clone)]
+pub struct Handle(Valve);
+
+impl Handle {
+    /// Wrap a [`Stream`] so that it ends once this handle is closed.
+    pub fn wrap<S: Stream>(&self, stream: S) -> stream_cancel::Valved<S> {
+        self.0.wrap(stream)
+    }
+}
+
+impl Future for Handle {
+    type Item = ();
+    type Error = ();
+
+    /// Resolves once this handle is closed.
+    fn poll(&mut self) -> Poll<(), ()> {
+        match self.0.wrap(Pending).poll() {
+            Ok(Async::Ready(_)) => Ok(Async::Ready(())),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(()) => Err(()),
+        }
+    }
+}
+
 /// Describes the possible reasons for the runtime to unexpectedly stop (that is, not stop because
 /// of a signal).
 #[derive(Debug)]
@@ -33,6 +211,14 @@ pub enum Shutdown<T, E> {
     NoRuntime(io::Error),
     /// A Unix signal handler failed.
     SignalError(io::Error),
+    /// A [`Safeword::grace_period`] timer failed.
+    TimerError(timer::Error),
+    /// The future passed to [`Safeword::run_graceful`] did not finish within the configured
+    /// [`Safeword::grace_period`], so the runtime was stopped without it.
+    GracePeriodExceeded,
+    /// A second terminate signal arrived while [`Safeword::run_graceful`] was still draining its
+    /// future, so the runtime was stopped immediately instead of waiting any longer.
+    Forced,
 }
 
 impl<T, E> Display for Shutdown<T, E>
@@ -44,6 +230,9 @@ where
             Shutdown::FutureFinished(_) => write!(f, "unexpectedly finished!"),
             Shutdown::FutureErr(err) => err.fmt(f),
             Shutdown::NoRuntime(err) | Shutdown::SignalError(err) => Display::fmt(err, f),
+            Shutdown::TimerError(err) => Display::fmt(err, f),
+            Shutdown::GracePeriodExceeded => write!(f, "grace period exceeded"),
+            Shutdown::Forced => write!(f, "forced shutdown"),
         }
     }
 }
@@ -57,6 +246,7 @@ where
         match self {
             Shutdown::FutureErr(err) => Some(err),
             Shutdown::NoRuntime(err) | Shutdown::SignalError(err) => Some(err),
+            Shutdown::TimerError(err) => Some(err),
             _ => None,
         }
     }
@@ -65,10 +255,26 @@ where
 /// A modified [Tokio][tokio] runtime that exits early on a signal.
 ///
 /// The [`Default`] impl returns a `Safeword` that exits on SIGINT (Ctrl-C) or SIGTERM (what init
-/// systems normally use to terminate a process).
-#[derive(Debug)]
+/// systems normally use to terminate a process) on Unix, and on Ctrl-C on Windows.
 pub struct Safeword {
-    signals: Vec<c_int>,
+    signals: Vec<Signal>,
+    grace_period: Option<Duration>,
+    #[cfg(unix)]
+    reload_signals: Vec<(c_int, Box<dyn FnMut() + Send>)>,
+}
+
+impl Debug for Safeword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut debug = f.debug_struct("Safeword");
+        debug.field("signals", &self.signals);
+        debug.field("grace_period", &self.grace_period);
+        #[cfg(unix)]
+        debug.field(
+            "reload_signals",
+            &self.reload_signals.iter().map(|(n, _)| n).collect::<Vec<_>>(),
+        );
+        debug.finish()
+    }
 }
 
 impl Safeword {
@@ -76,49 +282,157 @@ impl Safeword {
     pub fn new() -> Safeword {
         Safeword {
             signals: Vec::new(),
+            grace_period: None,
+            #[cfg(unix)]
+            reload_signals: Vec::new(),
         }
     }
 
     /// Exit early on a Unix signal.
+    #[cfg(unix)]
     pub fn signal(mut self, signal: c_int) -> Safeword {
-        self.signals.push(signal);
+        self.signals.push(Signal::Unix(signal));
+        self
+    }
+
+    /// Exit early on Ctrl-C: SIGINT on Unix, or the `CTRL_C_EVENT` console event on Windows.
+    ///
+    /// Unlike [`Safeword::signal`], this is available on every platform Safeword builds on.
+    pub fn ctrl_c(mut self) -> Safeword {
+        self.signals.push(Signal::CtrlC);
+        self
+    }
+
+    /// Invoke `callback` every time `signal` is received, without stopping the runtime.
+    ///
+    /// Unlike [`Safeword::signal`], this doesn't cause [`Safeword::run`] or
+    /// [`Safeword::run_graceful`] to stop; it's meant for signals like `SIGHUP` that ask a
+    /// running application to reload its configuration rather than exit.
+    ///
+    /// It's fine to build a `Safeword` that only has reload signals configured (no
+    /// [`Safeword::signal`] or [`Safeword::ctrl_c`] calls at all): `run`/`run_graceful` will just
+    /// wait for the given future to finish on its own, since there's nothing configured to stop
+    /// it early.
+    #[cfg(unix)]
+    pub fn on_reload<F>(mut self, signal: c_int, callback: F) -> Safeword
+    where
+        F: FnMut() + Send + 'static,
+    {
+        self.reload_signals.push((signal, Box::new(callback)));
+        self
+    }
+
+    /// Bound how long [`Safeword::run_graceful`] will wait for its future to resolve after a
+    /// signal arrives.
+    ///
+    /// If the future has not finished within `duration` of the signal arriving, the runtime is
+    /// stopped anyway and [`Safeword::run_graceful`] returns `Err(Shutdown::GracePeriodExceeded)`.
+    /// Without a grace period, `run_graceful` waits for the future to finish no matter how long
+    /// that takes.
+    pub fn grace_period(mut self, duration: Duration) -> Safeword {
+        self.grace_period = Some(duration);
         self
     }
 
     /// Run the given [`Future`].
     ///
-    /// Returns `Ok(())` if the runtime was terminated by a configured signal. Returns `Err` if
-    /// anything else happens, including the `Future` exiting of its own volition, or if something
-    /// internal to Safeword fails.
-    pub fn run<F>(self, future: F) -> Result<(), Shutdown<F::Item, F::Error>>
+    /// Returns `Ok(Signal)` identifying which configured signal terminated the runtime. Returns
+    /// `Err` if anything else happens, including the `Future` exiting of its own volition, or if
+    /// something internal to Safeword fails.
+    pub fn run<F>(self, future: F) -> Result<Signal, Shutdown<F::Item, F::Error>>
     where
         F: Future + Send + 'static,
         F::Item: Send,
         F::Error: Send,
     {
-        match Runtime::new()
-            .map_err(Shutdown::NoRuntime)?
-            .block_on(
-                future.select2(future::select_all(self.signals.into_iter().map(|signal| {
-                    tokio_signal::unix::Signal::new(signal)
-                        .flatten_stream()
-                        .into_future()
-                        .map(|_| ())
-                        .map_err(|(err, _)| Shutdown::SignalError(err))
-                }))),
-            ) {
+        let mut runtime = Runtime::new().map_err(Shutdown::NoRuntime)?;
+        #[cfg(unix)]
+        spawn_reload_signals(&mut runtime, self.reload_signals);
+
+        match runtime.block_on(future.select2(signal_future(self.signals))) {
             Ok(Either::A((x, _))) => Err(Shutdown::FutureFinished(x)),
-            Ok(Either::B(_)) => Ok(()),
+            Ok(Either::B((signal, _))) => Ok(signal),
             Err(Either::A((err, _))) => Err(Shutdown::FutureErr(err)),
-            Err(Either::B(((err, _, _), _))) => Err(err),
+            Err(Either::B((err, _))) => Err(err),
+        }
+    }
+
+    /// Run the given [`Future`], letting it clean up after itself once a signal arrives.
+    ///
+    /// Unlike [`Safeword::run`], `f` is not given the future to run directly, but a function that
+    /// builds one from a [`Handle`]. When a configured signal arrives, Safeword closes the handle
+    /// (so any [`Stream`] wrapped with [`Handle::wrap`] ends on its own) and then waits for the
+    /// future `f` produced to finish, rather than abandoning it immediately. If a *second*
+    /// terminate signal arrives while still draining, the runtime is stopped immediately and
+    /// `Err(Shutdown::Forced)` is returned, the same way a second Ctrl-C kills a slow process.
+    ///
+    /// Returns `Ok(Signal)` identifying which configured signal terminated the runtime. Returns
+    /// `Err` if anything else happens, including the `Future` failing as it drains, or if
+    /// something internal to Safeword fails.
+    pub fn run_graceful<F, Fut>(self, f: F) -> Result<Signal, Shutdown<Fut::Item, Fut::Error>>
+    where
+        F: FnOnce(Handle) -> Fut,
+        Fut: Future + Send + 'static,
+        Fut::Item: Send,
+        Fut::Error: Send,
+    {
+        let (trigger, valve) = Valve::new();
+        let future = f(Handle(valve));
+        let mut runtime = Runtime::new().map_err(Shutdown::NoRuntime)?;
+        #[cfg(unix)]
+        spawn_reload_signals(&mut runtime, self.reload_signals);
+
+        match runtime.block_on(future.select2(first_signal(self.signals))) {
+            Ok(Either::A((x, _))) => Err(Shutdown::FutureFinished(x)),
+            Ok(Either::B(((signal, remaining), future))) => {
+                drop(trigger);
+
+                let forced = remaining
+                    .into_future()
+                    .map(|_| Shutdown::Forced)
+                    .map_err(|(err, _)| err);
+                type Abort<T, E> =
+                    Box<dyn Future<Item = Shutdown<T, E>, Error = Shutdown<T, E>> + Send>;
+                let abort: Abort<Fut::Item, Fut::Error> = match self.grace_period {
+                    Some(duration) => {
+                        let timeout = Delay::new(Instant::now() + duration)
+                            .map(|_| Shutdown::GracePeriodExceeded)
+                            .map_err(Shutdown::TimerError);
+                        Box::new(forced.select(timeout).map(|(x, _)| x).map_err(|(x, _)| x))
+                    }
+                    None => Box::new(forced),
+                };
+
+                match runtime.block_on(future.select2(abort)) {
+                    Ok(Either::A(_)) => Ok(signal),
+                    Ok(Either::B((shutdown, _))) => Err(shutdown),
+                    Err(Either::A((err, _))) => Err(Shutdown::FutureErr(err)),
+                    Err(Either::B((err, _))) => Err(err),
+                }
+            }
+            Err(Either::A((err, _))) => Err(Shutdown::FutureErr(err)),
+            Err(Either::B((err, _))) => Err(err),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Default for Safeword {
+    fn default() -> Safeword {
+        Safeword {
+            signals: vec![Signal::Unix(SIGINT), Signal::Unix(SIGTERM)],
+            grace_period: None,
+            reload_signals: Vec::new(),
         }
     }
 }
 
+#[cfg(windows)]
 impl Default for Safeword {
     fn default() -> Safeword {
         Safeword {
-            signals: vec![SIGINT, SIGTERM],
+            signals: vec![Signal::CtrlC],
+            grace_period: None,
         }
     }
 }